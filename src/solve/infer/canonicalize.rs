@@ -0,0 +1,74 @@
+use errors::*;
+use ir::*;
+use super::{InferenceTable, ObligationSolver};
+use super::var::*;
+
+/// The result of canonicalizing a type: `value` has every free type
+/// inference variable it mentioned replaced by a bound variable, numbered
+/// in first-occurrence order; `free_vars` records which inference variable
+/// each bound variable stands for, so the caller can map back later.
+pub struct Canonicalized {
+    pub value: Ty,
+    pub free_vars: Vec<TyInferenceVariable>,
+}
+
+impl InferenceTable {
+    /// Canonicalizes `ty`, replacing each free type inference variable
+    /// reached through `ty` itself or the type parameters of `Ty::Apply`
+    /// nodes with a bound variable. Lifetime and const parameters, and any
+    /// other kind of type (such as a projection), are copied unchanged, so
+    /// inference variables inside them are not replaced.
+    ///
+    /// Before doing so, drains the pending obligation queue via
+    /// `resolve_obligations_as_possible(solver)` — a binding a deferred
+    /// `AliasEq` goal was waiting on may have just been learned, and
+    /// letting it resolve now can remove a variable that would otherwise
+    /// get frozen into the canonical form. Any `Integer`/`Float`
+    /// variables left unconstrained after that are defaulted via
+    /// `apply_fallback`, so a canonical form never exposes a bare
+    /// `{integer}`/`{float}` variable to the rest of the solver.
+    pub fn canonicalize<S: ObligationSolver>(&mut self, solver: &mut S, ty: &Ty) -> Result<Canonicalized> {
+        self.resolve_obligations_as_possible(solver)?;
+        self.apply_fallback();
+
+        let mut free_vars = vec![];
+        let value = self.canonicalize_ty(ty, &mut free_vars);
+        Ok(Canonicalized { value, free_vars })
+    }
+
+    fn canonicalize_ty(&mut self, ty: &Ty, free_vars: &mut Vec<TyInferenceVariable>) -> Ty {
+        if let Some(bound) = self.normalize_shallow(ty, 0) {
+            return self.canonicalize_ty(&bound, free_vars);
+        }
+
+        match ty.inference_var() {
+            Some(var) => {
+                let index = free_vars.iter().position(|&v| v == var).unwrap_or_else(|| {
+                    free_vars.push(var);
+                    free_vars.len() - 1
+                });
+                Ty::Var(index)
+            }
+
+            None => match *ty {
+                Ty::Apply(ref apply) => Ty::Apply(ApplicationTy {
+                    name: apply.name.clone(),
+                    parameters: apply.parameters
+                        .iter()
+                        .map(|param| match *param {
+                            ParameterKind::Ty(ref ty) => {
+                                ParameterKind::Ty(self.canonicalize_ty(ty, free_vars))
+                            }
+                            ParameterKind::Lifetime(ref lifetime) => {
+                                ParameterKind::Lifetime(lifetime.clone())
+                            }
+                            ParameterKind::Const(ref c) => ParameterKind::Const(c.clone()),
+                        })
+                        .collect(),
+                }),
+
+                ref other => other.clone(),
+            },
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use ena::unify as ena;
 use errors::*;
 use ir::*;
+use std::collections::HashMap;
+use std::mem;
 
 mod canonicalize;
 mod normalize_deep;
@@ -11,8 +13,8 @@ mod var;
 #[cfg(test)] mod test;
 
 pub use self::canonicalize::Canonicalized;
-pub use self::unify::UnificationResult;
-pub use self::var::{TyInferenceVariable, LifetimeInferenceVariable};
+pub use self::unify::{UnificationResult, UnificationDatabase, TypeMismatch, TypeMismatchPathElem};
+pub use self::var::{TyInferenceVariable, LifetimeInferenceVariable, ConstInferenceVariable, TyVariableKind};
 use self::var::*;
 
 #[derive(Clone)]
@@ -21,6 +23,16 @@ pub struct InferenceTable {
     ty_vars: Vec<TyInferenceVariable>,
     lifetime_unify: ena::UnificationTable<LifetimeInferenceVariable>,
     lifetime_vars: Vec<LifetimeInferenceVariable>,
+    const_unify: ena::UnificationTable<ConstInferenceVariable>,
+    const_vars: Vec<ConstInferenceVariable>,
+    /// The type each const-generic variable was declared at, consulted by
+    /// `unify` so a const value is checked against its declared type as
+    /// well as structurally against its peer.
+    const_var_tys: HashMap<ConstInferenceVariable, Ty>,
+    /// Goals (typically `AliasEq` obligations deferred by `unify`) that
+    /// are not yet known to hold, but that a fresh variable binding might
+    /// unlock. Drained by `resolve_obligations_as_possible`.
+    obligations: Vec<InEnvironment<DomainGoal>>,
 }
 
 pub struct InferenceSnapshot {
@@ -28,9 +40,14 @@ pub struct InferenceSnapshot {
     ty_vars: Vec<TyInferenceVariable>,
     lifetime_unify_snapshot: ena::Snapshot<LifetimeInferenceVariable>,
     lifetime_vars: Vec<LifetimeInferenceVariable>,
+    const_unify_snapshot: ena::Snapshot<ConstInferenceVariable>,
+    const_vars: Vec<ConstInferenceVariable>,
+    const_var_tys: HashMap<ConstInferenceVariable, Ty>,
+    obligations: Vec<InEnvironment<DomainGoal>>,
 }
 
-pub type ParameterInferenceVariable = ParameterKind<TyInferenceVariable, LifetimeInferenceVariable>;
+pub type ParameterInferenceVariable =
+    ParameterKind<TyInferenceVariable, LifetimeInferenceVariable, ConstInferenceVariable>;
 
 impl InferenceTable {
     pub fn new() -> Self {
@@ -39,6 +56,10 @@ impl InferenceTable {
             ty_vars: vec![],
             lifetime_unify: ena::UnificationTable::new(),
             lifetime_vars: vec![],
+            const_unify: ena::UnificationTable::new(),
+            const_vars: vec![],
+            const_var_tys: HashMap::new(),
+            obligations: vec![],
         }
     }
 
@@ -48,17 +69,52 @@ impl InferenceTable {
         var
     }
 
+    /// Creates a fresh type variable that may only unify with an integral
+    /// scalar type (or another `Integer`/`General` variable), and which
+    /// defaults to `i32` if it is never constrained further.
+    pub fn new_integer_variable(&mut self, ui: UniverseIndex) -> TyInferenceVariable {
+        let var = self.ty_unify.new_key(InferenceValue::UnboundKinded(ui, TyVariableKind::Integer));
+        self.ty_vars.push(var);
+        var
+    }
+
+    /// Like `new_integer_variable`, but for floating-point literals; the
+    /// variable defaults to `f64`.
+    pub fn new_float_variable(&mut self, ui: UniverseIndex) -> TyInferenceVariable {
+        let var = self.ty_unify.new_key(InferenceValue::UnboundKinded(ui, TyVariableKind::Float));
+        self.ty_vars.push(var);
+        var
+    }
+
     pub fn new_lifetime_variable(&mut self, ui: UniverseIndex) -> LifetimeInferenceVariable {
         let var = self.lifetime_unify.new_key(InferenceValue::Unbound(ui));
         self.lifetime_vars.push(var);
         var
     }
 
-    pub fn new_parameter_variable(&mut self, ui: ParameterKind<UniverseIndex>)
+    /// Creates a fresh const-generic inference variable, to be unified
+    /// structurally (including against its declared `ty`) as the const
+    /// value becomes known.
+    pub fn new_const_variable(&mut self, ui: UniverseIndex, ty: Ty) -> ConstInferenceVariable {
+        let var = self.const_unify.new_key(InferenceValue::Unbound(ui));
+        self.const_vars.push(var);
+        self.const_var_tys.insert(var, ty);
+        var
+    }
+
+    pub fn probe_const_var(&mut self, var: ConstInferenceVariable) -> Option<Const> {
+        match self.const_unify.probe_value(var) {
+            InferenceValue::Unbound(_) | InferenceValue::UnboundKinded(_, _) => None,
+            InferenceValue::Bound(ref val) => Some(val.clone()),
+        }
+    }
+
+    pub fn new_parameter_variable(&mut self, ui: ParameterKind<UniverseIndex, UniverseIndex, (UniverseIndex, Ty)>)
                                   -> ParameterInferenceVariable {
         match ui {
             ParameterKind::Ty(ui) => ParameterKind::Ty(self.new_variable(ui)),
             ParameterKind::Lifetime(ui) => ParameterKind::Lifetime(self.new_lifetime_variable(ui)),
+            ParameterKind::Const((ui, ty)) => ParameterKind::Const(self.new_const_variable(ui, ty)),
         }
     }
 
@@ -70,26 +126,53 @@ impl InferenceTable {
         &self.lifetime_vars
     }
 
+    pub fn const_vars(&self) -> &[ConstInferenceVariable] {
+        &self.const_vars
+    }
+
     pub fn snapshot(&mut self) -> InferenceSnapshot {
         let ty_unify_snapshot = self.ty_unify.snapshot();
         let lifetime_unify_snapshot = self.lifetime_unify.snapshot();
+        let const_unify_snapshot = self.const_unify.snapshot();
         let ty_vars = self.ty_vars.clone();
         let lifetime_vars = self.lifetime_vars.clone();
-        InferenceSnapshot { ty_unify_snapshot, lifetime_unify_snapshot, ty_vars, lifetime_vars }
+        let const_vars = self.const_vars.clone();
+        let const_var_tys = self.const_var_tys.clone();
+        let obligations = self.obligations.clone();
+        InferenceSnapshot {
+            ty_unify_snapshot,
+            lifetime_unify_snapshot,
+            const_unify_snapshot,
+            ty_vars,
+            lifetime_vars,
+            const_vars,
+            const_var_tys,
+            obligations,
+        }
     }
 
     pub fn rollback_to(&mut self, snapshot: InferenceSnapshot) {
         self.ty_unify.rollback_to(snapshot.ty_unify_snapshot);
         self.lifetime_unify.rollback_to(snapshot.lifetime_unify_snapshot);
+        self.const_unify.rollback_to(snapshot.const_unify_snapshot);
         self.ty_vars = snapshot.ty_vars;
         self.lifetime_vars = snapshot.lifetime_vars;
+        self.const_vars = snapshot.const_vars;
+        self.const_var_tys = snapshot.const_var_tys;
+        self.obligations = snapshot.obligations;
     }
 
     pub fn commit(&mut self, snapshot: InferenceSnapshot) {
         self.ty_unify.commit(snapshot.ty_unify_snapshot);
         self.lifetime_unify.commit(snapshot.lifetime_unify_snapshot);
+        self.const_unify.commit(snapshot.const_unify_snapshot);
     }
 
+    /// Runs `op`, discarding any variable bindings it made if it returns
+    /// an error. When `op` fails because two types did not unify, the
+    /// error can be downcast to `TypeMismatch` for a message like
+    /// "expected `Foo<u32>`, found `Foo<bool>` at type argument 0" rather
+    /// than a bare unification failure.
     pub fn commit_if_ok<F, R>(&mut self, op: F) -> Result<R>
         where F: FnOnce(&mut Self) -> Result<R>
     {
@@ -121,7 +204,7 @@ impl InferenceTable {
                 } else {
                     let var = TyInferenceVariable::from_depth(depth - binders);
                     match self.ty_unify.probe_value(var) {
-                        InferenceValue::Unbound(_) => None,
+                        InferenceValue::Unbound(_) | InferenceValue::UnboundKinded(_, _) => None,
                         InferenceValue::Bound(ref val) => Some(val.up_shift(binders)),
                     }
                 }
@@ -137,17 +220,91 @@ impl InferenceTable {
 
     pub fn probe_var(&mut self, var: TyInferenceVariable) -> Option<Ty> {
         match self.ty_unify.probe_value(var) {
-            InferenceValue::Unbound(_) => None,
+            InferenceValue::Unbound(_) | InferenceValue::UnboundKinded(_, _) => None,
             InferenceValue::Bound(ref val) => Some(val.clone()),
         }
     }
 
     pub fn probe_lifetime_var(&mut self, var: LifetimeInferenceVariable) -> Option<Lifetime> {
         match self.lifetime_unify.probe_value(var) {
-            InferenceValue::Unbound(_) => None,
+            InferenceValue::Unbound(_) | InferenceValue::UnboundKinded(_, _) => None,
             InferenceValue::Bound(val) => Some(val.clone()),
         }
     }
+
+    /// Binds every still-unbound `Integer`/`Float` type variable to its
+    /// default (`i32`/`f64` respectively), mirroring the literal-inference
+    /// fallback a front-end performs once no further constraints can
+    /// arrive. `General` variables are left untouched. Called before
+    /// canonicalizing so that canonical forms never expose a bare
+    /// `{integer}`/`{float}` variable to the rest of the solver.
+    pub fn apply_fallback(&mut self) {
+        for &var in &self.ty_vars {
+            let fallback = match self.ty_unify.probe_value(var) {
+                InferenceValue::UnboundKinded(_, kind) => kind.fallback_ty(),
+                InferenceValue::Unbound(_) | InferenceValue::Bound(_) => None,
+            };
+
+            if let Some(ty) = fallback {
+                self.ty_unify.unify_var_value(var, InferenceValue::Bound(ty))
+                    .expect("binding a previously unbound variable cannot fail");
+            }
+        }
+    }
+
+    /// Queues `goal` to be retried by `resolve_obligations_as_possible`,
+    /// e.g. an `AliasEq` goal `unify` could not resolve immediately.
+    pub fn register_obligation(&mut self, goal: InEnvironment<DomainGoal>) {
+        self.obligations.push(goal);
+    }
+
+    /// Repeatedly attempts every pending obligation via `solver`, applying
+    /// whatever unifications solving a goal produces before moving on to
+    /// the next, and looping until a full pass makes no further progress
+    /// (a fixpoint). Obligations `solver` cannot make progress on are
+    /// kept in the queue rather than dropped, since a later unification
+    /// (performed by the caller, after this returns) may unstick them.
+    /// Call this before `canonicalize` so freshly-learned bindings get a
+    /// chance to resolve obligations before they are frozen into bound
+    /// variables.
+    pub fn resolve_obligations_as_possible<S: ObligationSolver>(&mut self, solver: &mut S) -> Result<()> {
+        loop {
+            let mut progress = false;
+            let mut stalled = vec![];
+
+            for goal in mem::replace(&mut self.obligations, vec![]) {
+                match solver.solve(self, &goal)? {
+                    true => progress = true,
+                    false => stalled.push(goal),
+                }
+            }
+
+            // `solver.solve` may itself have called `register_obligation`
+            // for newly-discovered goals (e.g. a fresh `AliasEq` deferred
+            // by `unify`); those landed in `self.obligations` while the
+            // loop above was still running, so fold them into `stalled`
+            // instead of letting this overwrite drop them.
+            stalled.extend(mem::replace(&mut self.obligations, vec![]));
+            self.obligations = stalled;
+
+            if !progress {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Attempts to discharge a single deferred obligation, used by
+/// `InferenceTable::resolve_obligations_as_possible`.
+pub trait ObligationSolver {
+    /// Tries to make progress on `goal` against `table` (e.g. by unifying
+    /// a normalized projection with its expected type). Returns `Ok(true)`
+    /// if `goal` is solved, in which case it is removed from the queue
+    /// (any variables bound while solving it may unlock other
+    /// obligations), `Ok(false)` if `goal` is still stalled but not known
+    /// to be impossible, in which case it stays queued, or `Err` if `goal`
+    /// can never be satisfied.
+    fn solve(&mut self, table: &mut InferenceTable, goal: &InEnvironment<DomainGoal>) -> Result<bool>;
 }
 
 impl Ty {
@@ -168,6 +325,84 @@ impl Ty {
     pub fn inference_var(&self) -> Option<TyInferenceVariable> {
         self.var().map(TyInferenceVariable::from_depth)
     }
+
+    /// Constructs the scalar type for the given integer width/signedness.
+    pub fn int(ty: IntTy) -> Ty {
+        Ty::Apply(ApplicationTy { name: TypeName::Scalar(Scalar::Int(ty)), parameters: vec![] })
+    }
+
+    /// Constructs the scalar type for the given float width.
+    pub fn float(ty: FloatTy) -> Ty {
+        Ty::Apply(ApplicationTy { name: TypeName::Scalar(Scalar::Float(ty)), parameters: vec![] })
+    }
+
+    /// True if `self` is one of the integral scalar types (`i8..i128`,
+    /// `u8..u128`).
+    pub fn is_integer_scalar(&self) -> bool {
+        match *self {
+            Ty::Apply(ApplicationTy { name: TypeName::Scalar(Scalar::Int(_)), .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// True if `self` is one of the floating-point scalar types (`f32`,
+    /// `f64`).
+    pub fn is_float_scalar(&self) -> bool {
+        match *self {
+            Ty::Apply(ApplicationTy { name: TypeName::Scalar(Scalar::Float(_)), .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+impl TypeName {
+    /// The `ItemId` of the struct/enum/trait/fn this name refers to, or
+    /// `None` for a built-in name (e.g. a scalar) that has no associated
+    /// item and therefore no declared variance.
+    pub fn as_item_id(&self) -> Option<ItemId> {
+        match *self {
+            TypeName::ItemId(id) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+impl Const {
+    /// If this is a `Const::Var(d)`, returns `Some(d)` else `None`.
+    pub fn var(&self) -> Option<usize> {
+        if let Const::Var(depth) = *self {
+            Some(depth)
+        } else {
+            None
+        }
+    }
+
+    /// If this is a `Const::Var`, returns the `ConstInferenceVariable` it
+    /// represents. Only makes sense if `self` is known not to appear
+    /// inside of any binders, since otherwise the depth would have to be
+    /// adjusted to account for those binders.
+    pub fn inference_var(&self) -> Option<ConstInferenceVariable> {
+        self.var().map(ConstInferenceVariable::from_depth)
+    }
+
+    /// The type this const value was declared at. Panics on a free
+    /// variable, since callers are expected to have already handled that
+    /// case via `inference_var`.
+    pub fn ty(&self) -> Ty {
+        match *self {
+            Const::Value(ref value) => value.ty.clone(),
+            Const::Var(_) => panic!("Const::ty called on a free variable"),
+        }
+    }
+
+    /// The scalar this const value holds. Panics on a free variable; see
+    /// `ty`.
+    pub fn value(&self) -> &ConstScalar {
+        match *self {
+            Const::Value(ref value) => &value.value,
+            Const::Var(_) => panic!("Const::value called on a free variable"),
+        }
+    }
 }
 
 impl Lifetime {
@@ -210,6 +445,14 @@ impl Substitution {
             }
         }
 
+        for c in self.consts.values() {
+            if let Some(var) = c.inference_var() {
+                if in_infer.probe_const_var(var).is_some() {
+                    return false;
+                }
+            }
+        }
+
         true
     }
 }
\ No newline at end of file
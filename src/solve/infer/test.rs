@@ -0,0 +1,122 @@
+use errors::*;
+use ir::*;
+use super::*;
+
+#[test]
+fn ty_variable_kind_unify() {
+    use self::TyVariableKind::*;
+    assert_eq!(General.unify(General), Some(General));
+    assert_eq!(General.unify(Integer), Some(Integer));
+    assert_eq!(Float.unify(General), Some(Float));
+    assert_eq!(Integer.unify(Integer), Some(Integer));
+    assert_eq!(Float.unify(Float), Some(Float));
+    assert_eq!(Integer.unify(Float), None);
+    assert_eq!(Float.unify(Integer), None);
+}
+
+#[test]
+fn ty_variable_kind_admits() {
+    use self::TyVariableKind::*;
+    let int_ty = Ty::int(IntTy::I32);
+    let float_ty = Ty::float(FloatTy::F64);
+
+    assert!(General.admits(&int_ty));
+    assert!(General.admits(&float_ty));
+
+    assert!(Integer.admits(&int_ty));
+    assert!(!Integer.admits(&float_ty));
+
+    assert!(Float.admits(&float_ty));
+    assert!(!Float.admits(&int_ty));
+}
+
+#[test]
+fn apply_fallback_defaults_kinded_variables_only() {
+    let mut table = InferenceTable::new();
+    let ui = UniverseIndex::root();
+
+    let int_var = table.new_integer_variable(ui);
+    let float_var = table.new_float_variable(ui);
+    let general_var = table.new_variable(ui);
+
+    table.apply_fallback();
+
+    assert_eq!(table.probe_var(int_var), Some(Ty::int(IntTy::I32)));
+    assert_eq!(table.probe_var(float_var), Some(Ty::float(FloatTy::F64)));
+    assert_eq!(table.probe_var(general_var), None);
+}
+
+#[test]
+fn const_unification_checks_declared_type() {
+    let mut table = InferenceTable::new();
+    let ui = UniverseIndex::root();
+
+    let a = table.new_const_variable(ui, Ty::int(IntTy::I32));
+    let b = table.new_const_variable(ui, Ty::int(IntTy::U32));
+    assert!(table.unify_const_const(&a.to_const(), &b.to_const()).is_err());
+
+    let mut table = InferenceTable::new();
+    let ui = UniverseIndex::root();
+    let a = table.new_const_variable(ui, Ty::int(IntTy::I32));
+    let b = table.new_const_variable(ui, Ty::int(IntTy::I32));
+    assert!(table.unify_const_const(&a.to_const(), &b.to_const()).is_ok());
+}
+
+fn dummy_goal(lifetime: usize) -> InEnvironment<DomainGoal> {
+    InEnvironment::empty(DomainGoal::Outlives(OutlivesGoal {
+        a: Lifetime::Var(lifetime),
+        b: Lifetime::Var(lifetime),
+    }))
+}
+
+struct AlwaysStall;
+
+impl ObligationSolver for AlwaysStall {
+    fn solve(&mut self, _table: &mut InferenceTable, _goal: &InEnvironment<DomainGoal>) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[test]
+fn resolve_obligations_keeps_stalled_obligations() {
+    let mut table = InferenceTable::new();
+    table.register_obligation(dummy_goal(0));
+
+    table.resolve_obligations_as_possible(&mut AlwaysStall).unwrap();
+
+    assert_eq!(table.obligations.len(), 1);
+}
+
+/// Solves the first goal it is ever asked about, but while doing so
+/// registers a second, unrelated obligation (mirroring a solver that
+/// discovers a fresh `AliasEq` goal as a side effect of making progress).
+/// Every later call just stalls. This exercises the exact bug fixed in
+/// `resolve_obligations_as_possible`: obligations registered mid-pass must
+/// survive to the next round rather than being dropped when the pass's
+/// `stalled` list overwrites `self.obligations`.
+struct RegistersMidPass {
+    solved_first: bool,
+}
+
+impl ObligationSolver for RegistersMidPass {
+    fn solve(&mut self, table: &mut InferenceTable, _goal: &InEnvironment<DomainGoal>) -> Result<bool> {
+        if !self.solved_first {
+            self.solved_first = true;
+            table.register_obligation(dummy_goal(1));
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+#[test]
+fn resolve_obligations_does_not_drop_goals_registered_mid_pass() {
+    let mut table = InferenceTable::new();
+    table.register_obligation(dummy_goal(0));
+
+    let mut solver = RegistersMidPass { solved_first: false };
+    table.resolve_obligations_as_possible(&mut solver).unwrap();
+
+    assert_eq!(table.obligations.len(), 1);
+}
@@ -0,0 +1,369 @@
+use errors::*;
+use ir::*;
+use std::fmt;
+use super::{InferenceTable, InferenceValue};
+use super::var::*;
+
+/// One step of the structural path descended through the `zip` of two
+/// types to reach the point where they actually disagreed.
+#[derive(Clone, Debug)]
+pub enum TypeMismatchPathElem {
+    TypeArgument(usize),
+}
+
+impl fmt::Display for TypeMismatchPathElem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TypeMismatchPathElem::TypeArgument(i) => write!(f, "type argument {}", i),
+        }
+    }
+}
+
+/// A unification failure that records not just the two sub-terms that
+/// failed to match, but the structural path descended to reach them, so
+/// callers can render e.g. "expected `Foo<u32>`, found `Foo<bool>` at
+/// type argument 0" instead of a bare "cannot unify" message.
+#[derive(Clone, Debug)]
+pub struct TypeMismatch {
+    pub expected: Ty,
+    pub found: Ty,
+    pub path: Vec<TypeMismatchPathElem>,
+}
+
+impl TypeMismatch {
+    fn leaf(expected: Ty, found: Ty) -> TypeMismatch {
+        TypeMismatch { expected, found, path: vec![] }
+    }
+
+    fn push_path(mut self, elem: TypeMismatchPathElem) -> TypeMismatch {
+        self.path.insert(0, elem);
+        self
+    }
+}
+
+impl fmt::Display for TypeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected `{:?}`, found `{:?}`", self.expected, self.found)?;
+
+        let mut path = self.path.iter();
+        if let Some(elem) = path.next() {
+            write!(f, " at {}", elem)?;
+            for elem in path {
+                write!(f, ", {}", elem)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Fail for TypeMismatch {}
+
+/// If `err` wraps a `TypeMismatch`, prepends `elem` to its path and
+/// returns the (still boxed) error; otherwise passes `err` through
+/// unchanged. This is how the unifier accumulates a structural path as a
+/// leaf mismatch unwinds back up through nested `zip`s.
+fn push_path(err: Error, elem: TypeMismatchPathElem) -> Error {
+    match err.downcast::<TypeMismatch>() {
+        Ok(mismatch) => Error::from(mismatch.push_path(elem)),
+        Err(err) => err,
+    }
+}
+
+/// If `err` wraps a `TypeMismatch`, replaces its `expected`/`found` with
+/// the top-level types unification was originally asked to compare,
+/// keeping the path accumulated on the way down to the actual point of
+/// divergence. Without this, a mismatch found several `zip` levels deep
+/// (e.g. inside a type argument) would report those inner sub-terms as
+/// `expected`/`found` rather than the outer types the caller cares about
+/// — "expected `u32`, found `bool`" instead of "expected `Foo<u32>`,
+/// found `Foo<bool>` at type argument 0".
+fn attach_outer_types(err: Error, expected: &Ty, found: &Ty) -> Error {
+    match err.downcast::<TypeMismatch>() {
+        Ok(mismatch) => Error::from(TypeMismatch {
+            expected: expected.clone(),
+            found: found.clone(),
+            path: mismatch.path,
+        }),
+        Err(err) => err,
+    }
+}
+
+/// Hook an integration like rust-analyzer plugs in so that structural
+/// unification can make progress through associated-type projections and
+/// respect a trait or fn's declared variance, rather than treating a
+/// projection as an opaque leaf.
+pub trait UnificationDatabase {
+    /// Normalizes `projection` to its underlying value, if that can be
+    /// determined on the spot (e.g. because the self type is a concrete
+    /// struct with a known `impl`). Returns `None` if normalization would
+    /// require solving a goal (e.g. the self type is still a variable);
+    /// in that case unification registers a deferred `AliasEq` goal
+    /// instead of failing.
+    fn normalize_projection_ty(&self, projection: &ProjectionTy) -> Option<Ty>;
+
+    /// The declared variance of each generic parameter of the trait or fn
+    /// `item_id`. Type parameters are still compared invariantly
+    /// regardless of their declared variance (relating type parameters
+    /// under co/contravariance needs real subtyping, which this unifier
+    /// does not implement); lifetime parameters use the declared variance
+    /// to choose between full equality (`Invariant`) and a one-directional
+    /// outlives obligation (`Covariant`/`Contravariant`), mirroring how
+    /// `'a: 'b` differs from `'a == 'b`.
+    fn variances(&self, item_id: ItemId) -> &[Variance];
+}
+
+/// Goals discovered as a side effect of a successful unification: region
+/// obligations, and `AliasEq` goals deferred because a projection could
+/// not be normalized immediately (see `UnificationDatabase`).
+#[derive(Clone, Debug, Default)]
+pub struct UnificationResult {
+    pub goals: Vec<InEnvironment<DomainGoal>>,
+}
+
+impl InferenceTable {
+    /// Attempts to unify `a` and `b`, recording any variable bindings this
+    /// requires. On success, any goals that unification could not resolve
+    /// on the spot (e.g. region constraints, or a projection that `db`
+    /// could not normalize) are returned for the solver to pick up. On
+    /// failure, bindings made before the failing point are not undone, so
+    /// callers typically wrap this in `commit_if_ok`.
+    pub fn unify_ty_ty(&mut self, a: &Ty, b: &Ty) -> Result<UnificationResult> {
+        self.unify_ty_ty_in(None, a, b)
+    }
+
+    /// Like `unify_ty_ty`, but consults `db` to normalize associated-type
+    /// projections on the fly instead of treating them as opaque leaves.
+    pub fn unify_ty_ty_in(&mut self, db: Option<&UnificationDatabase>, a: &Ty, b: &Ty)
+                           -> Result<UnificationResult> {
+        let mut unifier = Unifier::new(self, db);
+        unifier.unify_ty_ty(a, b).map_err(|e| attach_outer_types(e, a, b))?;
+        Ok(unifier.into_result())
+    }
+
+    pub fn unify_lifetime_lifetime(&mut self, a: &Lifetime, b: &Lifetime) -> Result<UnificationResult> {
+        let mut unifier = Unifier::new(self, None);
+        unifier.unify_lifetime_lifetime(a, b)?;
+        Ok(unifier.into_result())
+    }
+
+    pub fn unify_const_const(&mut self, a: &Const, b: &Const) -> Result<UnificationResult> {
+        let mut unifier = Unifier::new(self, None);
+        unifier.unify_const_const(a, b)?;
+        Ok(unifier.into_result())
+    }
+}
+
+struct Unifier<'t> {
+    table: &'t mut InferenceTable,
+    db: Option<&'t UnificationDatabase>,
+    goals: Vec<InEnvironment<DomainGoal>>,
+}
+
+impl<'t> Unifier<'t> {
+    fn new(table: &'t mut InferenceTable, db: Option<&'t UnificationDatabase>) -> Self {
+        Unifier { table, db, goals: vec![] }
+    }
+
+    fn into_result(self) -> UnificationResult {
+        UnificationResult { goals: self.goals }
+    }
+
+    fn unify_ty_ty(&mut self, a: &Ty, b: &Ty) -> Result<()> {
+        let a = self.table.normalize_shallow(a, 0).unwrap_or_else(|| a.clone());
+        let b = self.table.normalize_shallow(b, 0).unwrap_or_else(|| b.clone());
+
+        match (a.inference_var(), b.inference_var()) {
+            (Some(var_a), Some(var_b)) => return self.unify_var_var(var_a, var_b),
+            (Some(var), None) => return self.unify_var_ty(var, &b),
+            (None, Some(var)) => return self.unify_var_ty(var, &a),
+            (None, None) => {}
+        }
+
+        match (&a, &b) {
+            (&Ty::Apply(ref apply_a), &Ty::Apply(ref apply_b)) => {
+                if apply_a.name != apply_b.name || apply_a.parameters.len() != apply_b.parameters.len() {
+                    return Err(Error::from(TypeMismatch::leaf(a.clone(), b.clone())));
+                }
+
+                let variances = self.db.and_then(|db| {
+                    apply_a.name.as_item_id().map(|id| db.variances(id))
+                });
+
+                for (i, (param_a, param_b)) in apply_a.parameters.iter().zip(&apply_b.parameters).enumerate() {
+                    let variance = variances.and_then(|vs| vs.get(i).cloned()).unwrap_or(Variance::Invariant);
+                    self.unify_parameter_parameter_variance(variance, param_a, param_b)
+                        .map_err(|e| push_path(e, TypeMismatchPathElem::TypeArgument(i)))?;
+                }
+
+                Ok(())
+            }
+
+            (&Ty::Projection(ref proj), _) => self.unify_projection_ty(proj, &b),
+            (_, &Ty::Projection(ref proj)) => self.unify_projection_ty(proj, &a),
+
+            _ => Err(Error::from(TypeMismatch::leaf(a.clone(), b.clone()))),
+        }
+    }
+
+    /// Unifies an associated-type projection against `other`. If `db` can
+    /// normalize the projection on the spot, unifies the normalized form
+    /// against `other` as usual; otherwise defers an `AliasEq` goal for
+    /// the solver to resolve once more is known, rather than failing.
+    fn unify_projection_ty(&mut self, proj: &ProjectionTy, other: &Ty) -> Result<()> {
+        if let Some(normalized) = self.db.and_then(|db| db.normalize_projection_ty(proj)) {
+            return self.unify_ty_ty(&normalized, other);
+        }
+
+        self.goals.push(InEnvironment::empty(DomainGoal::AliasEq(AliasEq {
+            alias: proj.clone(),
+            ty: other.clone(),
+        })));
+
+        Ok(())
+    }
+
+    fn unify_parameter_parameter(&mut self, a: &Parameter, b: &Parameter) -> Result<()> {
+        match (a, b) {
+            (&ParameterKind::Ty(ref a), &ParameterKind::Ty(ref b)) => self.unify_ty_ty(a, b),
+            (&ParameterKind::Lifetime(ref a), &ParameterKind::Lifetime(ref b)) => {
+                self.unify_lifetime_lifetime(a, b)
+            }
+            (&ParameterKind::Const(ref a), &ParameterKind::Const(ref b)) => {
+                self.unify_const_const(a, b)
+            }
+            (a, b) => bail!("mismatched parameter kinds: `{:?}` vs `{:?}`", a, b),
+        }
+    }
+
+    /// Like `unify_parameter_parameter`, but relates a lifetime parameter
+    /// according to `variance` rather than always demanding equality (see
+    /// `UnificationDatabase::variances`). Type and const parameters are
+    /// unaffected by variance and always unified invariantly.
+    fn unify_parameter_parameter_variance(&mut self, variance: Variance, a: &Parameter, b: &Parameter)
+                                          -> Result<()> {
+        match (a, b) {
+            (&ParameterKind::Lifetime(ref a), &ParameterKind::Lifetime(ref b)) => {
+                self.relate_lifetimes(variance, a, b)
+            }
+            (a, b) => self.unify_parameter_parameter(a, b),
+        }
+    }
+
+    /// Relates two lifetimes per `variance`: `Invariant` requires them to
+    /// unify as equal, while `Covariant`/`Contravariant` instead register
+    /// a one-directional outlives obligation for the solver to discharge.
+    fn relate_lifetimes(&mut self, variance: Variance, a: &Lifetime, b: &Lifetime) -> Result<()> {
+        match variance {
+            Variance::Invariant => self.unify_lifetime_lifetime(a, b),
+            Variance::Covariant => {
+                self.push_outlives(*a, *b);
+                Ok(())
+            }
+            Variance::Contravariant => {
+                self.push_outlives(*b, *a);
+                Ok(())
+            }
+        }
+    }
+
+    /// Registers an obligation that `longer` outlives `shorter`.
+    fn push_outlives(&mut self, longer: Lifetime, shorter: Lifetime) {
+        self.goals.push(InEnvironment::empty(DomainGoal::Outlives(OutlivesGoal { a: longer, b: shorter })));
+    }
+
+    /// Unifies two const values structurally. Free const variables unify
+    /// with one another (or bind to a concrete value) exactly like type
+    /// variables; concrete values must additionally agree on their
+    /// declared type, since `5u8` and `5u32` are not interchangeable even
+    /// though their values match.
+    fn unify_const_const(&mut self, a: &Const, b: &Const) -> Result<()> {
+        let a = a.inference_var().and_then(|v| self.table.probe_const_var(v)).unwrap_or_else(|| a.clone());
+        let b = b.inference_var().and_then(|v| self.table.probe_const_var(v)).unwrap_or_else(|| b.clone());
+
+        match (a.inference_var(), b.inference_var()) {
+            (Some(var_a), Some(var_b)) => {
+                if self.table.const_var_tys.get(&var_a) != self.table.const_var_tys.get(&var_b) {
+                    bail!("cannot unify const variables declared at different types");
+                }
+
+                return self.table.const_unify.unify_var_var(var_a, var_b)
+                    .map_err(|_| format_err!("cannot unify incompatible const variables"));
+            }
+            (Some(var), None) => return self.bind_const_var(var, &b),
+            (None, Some(var)) => return self.bind_const_var(var, &a),
+            (None, None) => {}
+        }
+
+        if a.ty() != b.ty() {
+            bail!("cannot unify const `{:?}` of type `{:?}` with const `{:?}` of type `{:?}`",
+                  a, a.ty(), b, b.ty());
+        }
+
+        if a.value() != b.value() {
+            bail!("cannot unify const value `{:?}` with `{:?}`", a.value(), b.value());
+        }
+
+        Ok(())
+    }
+
+    fn bind_const_var(&mut self, var: ConstInferenceVariable, value: &Const) -> Result<()> {
+        if let Some(declared_ty) = self.table.const_var_tys.get(&var).cloned() {
+            if declared_ty != value.ty() {
+                bail!("cannot bind const variable of type `{:?}` to `{:?}` of type `{:?}`",
+                      declared_ty, value, value.ty());
+            }
+        }
+
+        self.table.const_unify.unify_var_value(var, InferenceValue::Bound(value.clone()))
+            .map_err(|_| format_err!("cannot bind const variable to `{:?}`", value))
+    }
+
+    /// Binds `var` to `ty`, failing if `var`'s kind does not permit binding
+    /// to `ty` (e.g. an `Integer` variable meeting a struct or a float).
+    fn unify_var_ty(&mut self, var: TyInferenceVariable, ty: &Ty) -> Result<()> {
+        if let Some(ty_var) = ty.inference_var() {
+            return self.unify_var_var(var, ty_var);
+        }
+
+        let kind = self.table.ty_unify.probe_value(var).kind();
+        if !kind.admits(ty) {
+            return Err(Error::from(TypeMismatch::leaf(var.to_ty(), ty.clone())));
+        }
+
+        self.table.ty_unify.unify_var_value(var, InferenceValue::Bound(ty.clone()))
+            .map_err(|_| format_err!("cannot bind variable to `{:?}`", ty))
+    }
+
+    fn unify_var_var(&mut self, a: TyInferenceVariable, b: TyInferenceVariable) -> Result<()> {
+        let kind_a = self.table.ty_unify.probe_value(a).kind();
+        let kind_b = self.table.ty_unify.probe_value(b).kind();
+        if kind_a.unify(kind_b).is_none() {
+            return Err(Error::from(TypeMismatch::leaf(a.to_ty(), b.to_ty())));
+        }
+
+        self.table.ty_unify.unify_var_var(a, b)
+            .map_err(|_| format_err!("cannot unify incompatible type variables"))
+    }
+
+    fn unify_lifetime_lifetime(&mut self, a: &Lifetime, b: &Lifetime) -> Result<()> {
+        let a = self.table.normalize_lifetime(a).unwrap_or(*a);
+        let b = self.table.normalize_lifetime(b).unwrap_or(*b);
+
+        match (a.inference_var(), b.inference_var()) {
+            (Some(var_a), Some(var_b)) => {
+                self.table.lifetime_unify.unify_var_var(var_a, var_b)
+                    .map_err(|_| format_err!("cannot unify incompatible lifetime variables"))
+            }
+            (Some(var), None) => self.bind_lifetime_var(var, b),
+            (None, Some(var)) => self.bind_lifetime_var(var, a),
+            (None, None) if a == b => Ok(()),
+            (None, None) => bail!("cannot unify lifetimes `{:?}` and `{:?}`", a, b),
+        }
+    }
+
+    fn bind_lifetime_var(&mut self, var: LifetimeInferenceVariable, lifetime: Lifetime) -> Result<()> {
+        self.table.lifetime_unify.unify_var_value(var, InferenceValue::Bound(lifetime))
+            .map_err(|_| format_err!("cannot bind lifetime variable to `{:?}`", lifetime))
+    }
+}
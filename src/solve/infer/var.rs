@@ -0,0 +1,242 @@
+use ena::unify::{UnifyKey, UnifyValue};
+use ir::*;
+use std::cmp::min;
+use std::fmt;
+use std::fmt::Debug;
+use std::u32;
+
+/// The kind of a not-yet-bound type variable. Most type variables are
+/// `General` and can unify with anything, but integer and float literals
+/// create variables that may only unify with a scalar of the matching
+/// flavor (or with another variable of a compatible kind), mirroring the
+/// `{integer}`/`{float}` inference rustc performs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TyVariableKind {
+    General,
+    Integer,
+    Float,
+}
+
+impl TyVariableKind {
+    /// The kind that results from unifying a variable of kind `self` with
+    /// one of kind `other`, or `None` if the two kinds can never agree.
+    pub fn unify(self, other: TyVariableKind) -> Option<TyVariableKind> {
+        use self::TyVariableKind::*;
+        match (self, other) {
+            (General, other) | (other, General) => Some(other),
+            (Integer, Integer) => Some(Integer),
+            (Float, Float) => Some(Float),
+            (Integer, Float) | (Float, Integer) => None,
+        }
+    }
+
+    /// True if `ty` is a scalar type this kind of variable is permitted
+    /// to bind to.
+    pub fn admits(self, ty: &Ty) -> bool {
+        match self {
+            TyVariableKind::General => true,
+            TyVariableKind::Integer => ty.is_integer_scalar(),
+            TyVariableKind::Float => ty.is_float_scalar(),
+        }
+    }
+
+    /// The type an unbound variable of this kind defaults to if nothing
+    /// ever constrains it further. `General` variables have no fallback.
+    pub fn fallback_ty(self) -> Option<Ty> {
+        match self {
+            TyVariableKind::General => None,
+            TyVariableKind::Integer => Some(Ty::int(IntTy::I32)),
+            TyVariableKind::Float => Some(Ty::float(FloatTy::F64)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TyInferenceVariable {
+    index: u32,
+}
+
+impl TyInferenceVariable {
+    pub fn from_depth(depth: usize) -> TyInferenceVariable {
+        assert!(depth < u32::MAX as usize);
+        TyInferenceVariable { index: depth as u32 }
+    }
+
+    pub fn to_ty(self) -> Ty {
+        Ty::Var(self.to_depth())
+    }
+
+    pub fn to_depth(self) -> usize {
+        self.index as usize
+    }
+}
+
+impl UnifyKey for TyInferenceVariable {
+    type Value = InferenceValue<Ty>;
+
+    fn index(&self) -> u32 {
+        self.index
+    }
+
+    fn from_index(i: u32) -> Self {
+        TyInferenceVariable { index: i }
+    }
+
+    fn tag() -> &'static str {
+        "TyInferenceVariable"
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LifetimeInferenceVariable {
+    index: u32,
+}
+
+impl LifetimeInferenceVariable {
+    pub fn from_depth(depth: usize) -> LifetimeInferenceVariable {
+        assert!(depth < u32::MAX as usize);
+        LifetimeInferenceVariable { index: depth as u32 }
+    }
+
+    pub fn to_lifetime(self) -> Lifetime {
+        Lifetime::Var(self.to_depth())
+    }
+
+    pub fn to_depth(self) -> usize {
+        self.index as usize
+    }
+}
+
+impl UnifyKey for LifetimeInferenceVariable {
+    type Value = InferenceValue<Lifetime>;
+
+    fn index(&self) -> u32 {
+        self.index
+    }
+
+    fn from_index(i: u32) -> Self {
+        LifetimeInferenceVariable { index: i }
+    }
+
+    fn tag() -> &'static str {
+        "LifetimeInferenceVariable"
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ConstInferenceVariable {
+    index: u32,
+}
+
+impl ConstInferenceVariable {
+    pub fn from_depth(depth: usize) -> ConstInferenceVariable {
+        assert!(depth < u32::MAX as usize);
+        ConstInferenceVariable { index: depth as u32 }
+    }
+
+    pub fn to_const(self) -> Const {
+        Const::Var(self.to_depth())
+    }
+
+    pub fn to_depth(self) -> usize {
+        self.index as usize
+    }
+}
+
+impl UnifyKey for ConstInferenceVariable {
+    type Value = InferenceValue<Const>;
+
+    fn index(&self) -> u32 {
+        self.index
+    }
+
+    fn from_index(i: u32) -> Self {
+        ConstInferenceVariable { index: i }
+    }
+
+    fn tag() -> &'static str {
+        "ConstInferenceVariable"
+    }
+}
+
+/// The value `ena` stores for each key in a unification table: either the
+/// variable is still unbound (and, for type variables, we remember its
+/// `TyVariableKind` alongside the universe it was created in), or it has
+/// been bound to a concrete `T`.
+#[derive(Clone, Debug)]
+pub enum InferenceValue<T> {
+    Unbound(UniverseIndex),
+    UnboundKinded(UniverseIndex, TyVariableKind),
+    Bound(T),
+}
+
+impl<T> InferenceValue<T> {
+    pub fn kind(&self) -> TyVariableKind {
+        match *self {
+            InferenceValue::UnboundKinded(_, kind) => kind,
+            InferenceValue::Unbound(_) | InferenceValue::Bound(_) => TyVariableKind::General,
+        }
+    }
+
+    pub fn universe(&self) -> Option<UniverseIndex> {
+        match *self {
+            InferenceValue::Unbound(ui) | InferenceValue::UnboundKinded(ui, _) => Some(ui),
+            InferenceValue::Bound(_) => None,
+        }
+    }
+}
+
+/// Error returned by `InferenceValue::unify_values` when two unbound type
+/// variables have kinds that can never agree (e.g. `Integer` meeting
+/// `Float`). Two unbound type variables only meet in `unify_var_var` in
+/// `unify.rs`, which pre-checks `TyVariableKind::unify` before reaching
+/// `ena`'s `unify_var_var`, and binding a variable to a value never fails,
+/// so this is currently unreachable in practice, but surfacing it as a real `Err`
+/// (rather than a panic behind `NoError`) means a future caller that skips
+/// the pre-check fails gracefully instead of aborting.
+#[derive(Copy, Clone, Debug)]
+pub struct IncompatibleKinds {
+    pub a: TyVariableKind,
+    pub b: TyVariableKind,
+}
+
+impl fmt::Display for IncompatibleKinds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot unify a `{:?}` variable with a `{:?}` variable", self.a, self.b)
+    }
+}
+
+impl<T: Clone + Debug> UnifyValue for InferenceValue<T> {
+    type Error = IncompatibleKinds;
+
+    fn unify_values(a: &Self, b: &Self) -> Result<Self, IncompatibleKinds> {
+        match (a, b) {
+            (&InferenceValue::Unbound(ui_a), &InferenceValue::Unbound(ui_b)) => {
+                Ok(InferenceValue::Unbound(min(ui_a, ui_b)))
+            }
+
+            (&InferenceValue::UnboundKinded(ui_a, kind_a), &InferenceValue::Unbound(ui_b)) |
+            (&InferenceValue::Unbound(ui_b), &InferenceValue::UnboundKinded(ui_a, kind_a)) => {
+                Ok(InferenceValue::UnboundKinded(min(ui_a, ui_b), kind_a))
+            }
+
+            (&InferenceValue::UnboundKinded(ui_a, kind_a),
+             &InferenceValue::UnboundKinded(ui_b, kind_b)) => {
+                match kind_a.unify(kind_b) {
+                    Some(kind) => Ok(InferenceValue::UnboundKinded(min(ui_a, ui_b), kind)),
+                    None => Err(IncompatibleKinds { a: kind_a, b: kind_b }),
+                }
+            }
+
+            (&InferenceValue::Unbound(_), &InferenceValue::Bound(_)) |
+            (&InferenceValue::UnboundKinded(_, _), &InferenceValue::Bound(_)) => Ok(b.clone()),
+
+            (&InferenceValue::Bound(_), &InferenceValue::Unbound(_)) |
+            (&InferenceValue::Bound(_), &InferenceValue::UnboundKinded(_, _)) => Ok(a.clone()),
+
+            (&InferenceValue::Bound(..), &InferenceValue::Bound(..)) => {
+                panic!("we should never be asked to unify two bound things")
+            }
+        }
+    }
+}